@@ -6,26 +6,35 @@
 ///
 /// > An `MVar t` is mutable location that is either empty or contains a value of type `t`. It has two fundamental operations: `putMVar` which fills an MVar if it is empty and blocks otherwise, and `takeMVar` which empties an MVar if it is full and blocks otherwise.
 use core::future::Future;
+use core::marker::PhantomPinned;
 use core::mem::MaybeUninit;
 use core::pin::Pin;
+use core::ptr::NonNull;
 use core::sync::atomic::Ordering;
-use core::task::Poll;
-use futures::task::AtomicWaker;
+use core::task::{Poll, Waker};
 
 use core::sync::atomic::AtomicU8;
 
-// (empty, filling, emptying, full)
+mod once;
+pub use once::{AsyncOnce, GetOrInitFuture};
+
+// (empty, filling, emptying, full, reading, swapping)
 const MVAR_EMPTY: u8 = 1;
 const MVAR_FILLING: u8 = 2;
 const MVAR_EMPTYING: u8 = 4;
 const MVAR_FULL: u8 = 8;
+// `read()` and `swap()` never pass through `MVAR_EMPTY`: they go straight from
+// `MVAR_FULL` into one of these and back, so a waiting putter never sees the
+// cell as empty and can't race in.
+const MVAR_READING: u8 = 16;
+const MVAR_SWAPPING: u8 = 32;
 
 #[derive(Debug)]
 pub struct MVar<T> {
     item: UnsafeCell<MaybeUninit<T>>,
     state: AtomicU8,
-    take_waker: AtomicWaker,
-    put_waker: AtomicWaker,
+    take_waiters: WaiterList,
+    put_waiters: WaiterList,
 }
 
 unsafe impl<T: Sync> Sync for MVar<T> {}
@@ -35,8 +44,8 @@ impl<T> MVar<T> {
         Self {
             item: UnsafeCell::new(MaybeUninit::uninit()),
             state: AtomicU8::new(MVAR_EMPTY),
-            take_waker: AtomicWaker::new(),
-            put_waker: AtomicWaker::new(),
+            take_waiters: WaiterList::new(),
+            put_waiters: WaiterList::new(),
         }
     }
 
@@ -44,19 +53,25 @@ impl<T> MVar<T> {
         Self {
             item: UnsafeCell::new(MaybeUninit::new(item)),
             state: AtomicU8::new(MVAR_FULL),
-            take_waker: AtomicWaker::new(),
-            put_waker: AtomicWaker::new(),
+            take_waiters: WaiterList::new(),
+            put_waiters: WaiterList::new(),
         }
     }
 
-    pub const fn take(&self) -> TakeFuture<T> {
-        TakeFuture { mvar: self }
+    pub const fn take(&self) -> TakeFuture<'_, T> {
+        TakeFuture {
+            mvar: self,
+            node: UnsafeCell::new(WaiterNode::new()),
+            _pin: PhantomPinned,
+        }
     }
 
-    pub const fn put(&self, item: T) -> PutFuture<T> {
+    pub const fn put(&self, item: T) -> PutFuture<'_, T> {
         PutFuture {
             mvar: self,
             item: Some(item),
+            node: UnsafeCell::new(WaiterNode::new()),
+            _pin: PhantomPinned,
         }
     }
 
@@ -81,78 +96,480 @@ impl<T> MVar<T> {
             None
         }
     }
+
+    /// Deposit `value` into a cell the caller has already claimed by moving
+    /// `state` from `MVAR_EMPTY` to `MVAR_FILLING`, restoring it to full.
+    fn _put_back(&self, value: T) {
+        let mut muvalue = MaybeUninit::new(value);
+
+        self.item
+            .with_mut(|ptr| core::mem::swap(unsafe { &mut *ptr }, &mut muvalue));
+
+        self.state.store(MVAR_FULL, Ordering::SeqCst);
+    }
+
+    /// Take the value out without blocking, returning `None` if the cell is
+    /// currently empty (or mid-transition).
+    pub fn try_take(&self) -> Option<T> {
+        let value = self._take()?;
+        // a putter may now be able to make progress
+        self.put_waiters.wake_one();
+        Some(value)
+    }
+
+    /// Fill the cell without blocking. Returns the item back on failure, i.e.
+    /// if the cell is currently full (or mid-transition).
+    pub fn try_put(&self, item: T) -> Result<(), T> {
+        let can_put = self.state.compare_exchange(
+            MVAR_EMPTY,
+            MVAR_FILLING,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+
+        if let Ok(MVAR_EMPTY) = can_put {
+            let mut muvalue = MaybeUninit::new(item);
+
+            self.item
+                .with_mut(|ptr| core::mem::swap(unsafe { &mut *ptr }, &mut muvalue));
+
+            self.state.store(MVAR_FULL, Ordering::SeqCst);
+
+            // a taker may now be able to make progress
+            self.take_waiters.wake_one();
+
+            Ok(())
+        } else {
+            Err(item)
+        }
+    }
+
+    /// Whether the cell is currently empty. Cheap enough to branch on before
+    /// deciding whether to `.await` a blocking operation.
+    pub fn is_empty(&self) -> bool {
+        self.state.load(Ordering::Acquire) == MVAR_EMPTY
+    }
+
+    /// Whether the cell is currently full. Cheap enough to branch on before
+    /// deciding whether to `.await` a blocking operation.
+    pub fn is_full(&self) -> bool {
+        self.state.load(Ordering::Acquire) == MVAR_FULL
+    }
+}
+
+impl<T> MVar<T> {
+    pub const fn swap(&self, new: T) -> SwapFuture<'_, T> {
+        SwapFuture {
+            mvar: self,
+            new: Some(new),
+            node: UnsafeCell::new(WaiterNode::new()),
+            _pin: PhantomPinned,
+        }
+    }
+
+    fn _swap(&self, new: &mut Option<T>) -> Option<T> {
+        let can_swap = self.state.compare_exchange(
+            MVAR_FULL,
+            MVAR_SWAPPING,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+
+        if let Ok(MVAR_FULL) = can_swap {
+            let mut muvalue =
+                MaybeUninit::new(new.take().expect("SwapFuture polled after completion"));
+
+            self.item
+                .with_mut(|ptr| core::mem::swap(unsafe { &mut *ptr }, &mut muvalue));
+
+            self.state.store(MVAR_FULL, Ordering::SeqCst);
+
+            unsafe { Some(muvalue.assume_init()) }
+        } else {
+            None
+        }
+    }
+
+    pub const fn modify<F>(&self, f: F) -> ModifyFuture<'_, T, F>
+    where
+        F: FnOnce(T) -> T,
+    {
+        ModifyFuture {
+            mvar: self,
+            f: Some(f),
+            phase: ModifyPhase::Taking,
+            node: UnsafeCell::new(WaiterNode::new()),
+            _pin: PhantomPinned,
+        }
+    }
+}
+
+impl<T: Clone> MVar<T> {
+    pub const fn read(&self) -> ReadFuture<'_, T> {
+        ReadFuture {
+            mvar: self,
+            node: UnsafeCell::new(WaiterNode::new()),
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// Like `_take`, but leaves the cell full: clones the value out through a
+    /// shared read instead of swapping it out.
+    fn _read(&self) -> Option<T> {
+        let can_read = self.state.compare_exchange(
+            MVAR_FULL,
+            MVAR_READING,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+
+        if let Ok(MVAR_FULL) = can_read {
+            let value = self.item.with(|ptr| unsafe { (*ptr).assume_init_ref().clone() });
+
+            self.state.store(MVAR_FULL, Ordering::SeqCst);
+
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    /// Clone the value out without blocking, returning `None` if the cell is
+    /// currently empty (or mid-transition). Leaves the cell full.
+    pub fn try_read(&self) -> Option<T> {
+        let value = self._read()?;
+        // the cell is full again, never empty: wake another taker/reader, not a putter
+        self.take_waiters.wake_one();
+        Some(value)
+    }
 }
 
 #[must_use]
 pub struct TakeFuture<'a, T> {
     mvar: &'a MVar<T>,
+    node: UnsafeCell<WaiterNode>,
+    // the node above is linked into `mvar.take_waiters` by address once we're
+    // polled the first time, so this future must not move after that point
+    _pin: PhantomPinned,
 }
 
 impl<'a, T> Future for TakeFuture<'a, T> {
     type Output = T;
 
     fn poll(self: Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<Self::Output> {
-        // register before the computation
-        self.mvar.take_waker.register(cx.waker());
+        // SAFETY: we never move out of `self`, only read the (pin-stable) address
+        // of `node` to link it into the waiter list.
+        let this = unsafe { self.get_unchecked_mut() };
+        let node = NonNull::from(this.node.with_mut(|ptr| unsafe { &mut *ptr }));
+        let mut registered = false;
+
+        loop {
+            match this.mvar._take() {
+                Some(value) => {
+                    // we're done with our node; if we were still enrolled, leave the list
+                    this.mvar.take_waiters.remove(node);
 
-        match self.mvar._take() {
-            Some(value) => {
-                // wake after the computation
-                self.mvar.put_waker.wake();
+                    // wake after the computation: a putter can now make progress
+                    this.mvar.put_waiters.wake_one();
 
-                Poll::Ready(value)
+                    return Poll::Ready(value);
+                }
+                None if !registered => {
+                    // a put may complete (and find nobody queued to wake) in
+                    // the gap between the failed attempt above and enrolling
+                    // here, so re-check once more after registering
+                    this.mvar.take_waiters.register(node, cx.waker());
+                    registered = true;
+                }
+                None => return Poll::Pending,
             }
-            None => Poll::Pending,
         }
     }
 }
 
+impl<'a, T> Drop for TakeFuture<'a, T> {
+    fn drop(&mut self) {
+        let node = NonNull::from(self.node.with_mut(|ptr| unsafe { &mut *ptr }));
+        self.mvar.take_waiters.cancel(node);
+    }
+}
+
 #[must_use]
 pub struct PutFuture<'a, T> {
     item: Option<T>,
     mvar: &'a MVar<T>,
+    node: UnsafeCell<WaiterNode>,
+    _pin: PhantomPinned,
 }
 
-impl<'a, T: Unpin> Future for PutFuture<'a, T> {
+impl<'a, T> Future for PutFuture<'a, T> {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<Self::Output> {
-        // register before the computation
-        self.mvar.put_waker.register(cx.waker());
+        // SAFETY: see `TakeFuture::poll`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let node = NonNull::from(this.node.with_mut(|ptr| unsafe { &mut *ptr }));
+        let mut registered = false;
+
+        loop {
+            let can_put = this.mvar.state.compare_exchange(
+                MVAR_EMPTY,
+                MVAR_FILLING,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            );
+
+            if let Ok(MVAR_EMPTY) = can_put {
+                let opt_value = core::mem::take(&mut this.item);
+
+                match opt_value {
+                    Some(value) => {
+                        let mut muvalue = MaybeUninit::new(value);
+                        this.mvar
+                            .item
+                            .with_mut(|ptr| core::mem::swap(unsafe { &mut *ptr }, &mut muvalue));
+
+                        this.mvar.state.store(MVAR_FULL, Ordering::SeqCst);
+
+                        // we're done with our node; if we were still enrolled, leave the list
+                        this.mvar.put_waiters.remove(node);
+
+                        // wake after the computation: a taker can now make progress
+                        this.mvar.take_waiters.wake_one();
+                    }
+                    None => {
+                        unreachable!("the same PutFuture is used twice");
+                    }
+                }
 
-        let can_put = self.mvar.state.compare_exchange(
-            MVAR_EMPTY,
-            MVAR_FILLING,
-            Ordering::SeqCst,
-            Ordering::SeqCst,
-        );
+                return Poll::Ready(());
+            } else if !registered {
+                // a take may complete (and find nobody queued to wake) in the
+                // gap between the failed attempt above and enrolling here, so
+                // re-check once more after registering
+                this.mvar.put_waiters.register(node, cx.waker());
+                registered = true;
+            } else {
+                return Poll::Pending;
+            }
+        }
+    }
+}
 
-        if let Ok(MVAR_EMPTY) = can_put {
-            let reference = Pin::into_inner(self);
+impl<'a, T> Drop for PutFuture<'a, T> {
+    fn drop(&mut self) {
+        let node = NonNull::from(self.node.with_mut(|ptr| unsafe { &mut *ptr }));
+        self.mvar.put_waiters.cancel(node);
+    }
+}
+
+#[must_use]
+pub struct ReadFuture<'a, T> {
+    mvar: &'a MVar<T>,
+    node: UnsafeCell<WaiterNode>,
+    _pin: PhantomPinned,
+}
+
+impl<'a, T: Clone> Future for ReadFuture<'a, T> {
+    type Output = T;
 
-            let opt_value = core::mem::take(&mut reference.item);
+    fn poll(self: Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: see `TakeFuture::poll`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let node = NonNull::from(this.node.with_mut(|ptr| unsafe { &mut *ptr }));
+        let mut registered = false;
 
-            match opt_value {
+        loop {
+            match this.mvar._read() {
                 Some(value) => {
-                    let mut muvalue = MaybeUninit::new(value);
-                    reference
-                        .mvar
-                        .item
-                        .with_mut(|ptr| core::mem::swap(unsafe { &mut *ptr }, &mut muvalue));
+                    this.mvar.take_waiters.remove(node);
 
-                    reference.mvar.state.store(MVAR_FULL, Ordering::SeqCst);
+                    // the cell is full again, exactly as before we looked: wake
+                    // another taker/reader, but not a putter, since it never
+                    // became empty
+                    this.mvar.take_waiters.wake_one();
 
-                    // wake after the computation
-                    reference.mvar.take_waker.wake();
+                    return Poll::Ready(value);
                 }
-                None => {
-                    unreachable!("the same PutFuture is used twice");
+                None if !registered => {
+                    // a put may complete (and find nobody queued to wake) in
+                    // the gap between the failed attempt above and enrolling
+                    // here, so re-check once more after registering
+                    this.mvar.take_waiters.register(node, cx.waker());
+                    registered = true;
                 }
+                None => return Poll::Pending,
             }
+        }
+    }
+}
 
-            Poll::Ready(())
-        } else {
-            Poll::Pending
+impl<'a, T> Drop for ReadFuture<'a, T> {
+    fn drop(&mut self) {
+        let node = NonNull::from(self.node.with_mut(|ptr| unsafe { &mut *ptr }));
+        self.mvar.take_waiters.cancel(node);
+    }
+}
+
+#[must_use]
+pub struct SwapFuture<'a, T> {
+    mvar: &'a MVar<T>,
+    new: Option<T>,
+    node: UnsafeCell<WaiterNode>,
+    _pin: PhantomPinned,
+}
+
+impl<'a, T> Future for SwapFuture<'a, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: see `TakeFuture::poll`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let node = NonNull::from(this.node.with_mut(|ptr| unsafe { &mut *ptr }));
+        let mut registered = false;
+
+        loop {
+            match this.mvar._swap(&mut this.new) {
+                Some(old) => {
+                    this.mvar.take_waiters.remove(node);
+
+                    // the cell is full again (now with `new`), never empty:
+                    // wake another taker/reader, but not a putter
+                    this.mvar.take_waiters.wake_one();
+
+                    return Poll::Ready(old);
+                }
+                None if !registered => {
+                    // a concurrent put/read may complete (and find nobody
+                    // queued to wake) in the gap between the failed attempt
+                    // above and enrolling here, so re-check once more after
+                    // registering
+                    this.mvar.take_waiters.register(node, cx.waker());
+                    registered = true;
+                }
+                None => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<'a, T> Drop for SwapFuture<'a, T> {
+    fn drop(&mut self) {
+        let node = NonNull::from(self.node.with_mut(|ptr| unsafe { &mut *ptr }));
+        self.mvar.take_waiters.cancel(node);
+    }
+}
+
+enum ModifyPhase<T> {
+    /// haven't taken the value out of the cell yet
+    Taking,
+    /// took the original value out; still need to run `f` and put the
+    /// result back. `f` isn't invoked until the put-back is about to
+    /// succeed, so if the future is dropped while parked here, there's
+    /// nothing of `f`'s to discard: the untouched original is what's
+    /// restored.
+    Putting(T),
+}
+
+#[must_use]
+pub struct ModifyFuture<'a, T, F> {
+    mvar: &'a MVar<T>,
+    f: Option<F>,
+    phase: ModifyPhase<T>,
+    node: UnsafeCell<WaiterNode>,
+    _pin: PhantomPinned,
+}
+
+impl<'a, T, F> Future for ModifyFuture<'a, T, F>
+where
+    F: FnOnce(T) -> T,
+{
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: see `TakeFuture::poll`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let node = NonNull::from(this.node.with_mut(|ptr| unsafe { &mut *ptr }));
+        let mut registered = false;
+
+        loop {
+            match &mut this.phase {
+                ModifyPhase::Taking => match this.mvar._take() {
+                    Some(value) => {
+                        this.mvar.take_waiters.remove(node);
+                        this.phase = ModifyPhase::Putting(value);
+
+                        // we're about to wait on a different list (put_waiters)
+                        // if this phase also can't complete immediately
+                        registered = false;
+                    }
+                    None if !registered => {
+                        // a concurrent put may complete (and find nobody
+                        // queued to wake) in the gap between the failed
+                        // attempt above and enrolling here, so re-check once
+                        // more after registering
+                        this.mvar.take_waiters.register(node, cx.waker());
+                        registered = true;
+                    }
+                    None => return Poll::Pending,
+                },
+                ModifyPhase::Putting(_) => {
+                    let can_put = this.mvar.state.compare_exchange(
+                        MVAR_EMPTY,
+                        MVAR_FILLING,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    );
+
+                    if let Ok(MVAR_EMPTY) = can_put {
+                        let original =
+                            match core::mem::replace(&mut this.phase, ModifyPhase::Taking) {
+                                ModifyPhase::Putting(value) => value,
+                                ModifyPhase::Taking => unreachable!(),
+                            };
+
+                        let f = this.f.take().expect("ModifyFuture polled after completion");
+                        this.mvar._put_back(f(original));
+                        this.mvar.put_waiters.remove(node);
+                        this.mvar.take_waiters.wake_one();
+
+                        return Poll::Ready(());
+                    } else if !registered {
+                        // a concurrent take may complete (and find nobody
+                        // queued to wake) in the gap between the failed
+                        // attempt above and enrolling here, so re-check once
+                        // more after registering
+                        this.mvar.put_waiters.register(node, cx.waker());
+                        registered = true;
+                    } else {
+                        return Poll::Pending;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T, F> Drop for ModifyFuture<'a, T, F> {
+    fn drop(&mut self) {
+        let node = NonNull::from(self.node.with_mut(|ptr| unsafe { &mut *ptr }));
+
+        match core::mem::replace(&mut self.phase, ModifyPhase::Taking) {
+            ModifyPhase::Taking => {
+                self.mvar.take_waiters.cancel(node);
+            }
+            ModifyPhase::Putting(original) => {
+                // we took the value out but never got to run `f`/put a
+                // result back before being dropped; since `f` only runs once
+                // the put-back is about to succeed, `original` is untouched.
+                // Re-acquire the slot the same way `try_put` does rather
+                // than depositing unconditionally: another task may have
+                // refilled the cell while we were parked, and we must not
+                // clobber its value. In that case `try_put` fails and
+                // `original` is dropped; otherwise it is put back.
+                self.mvar.put_waiters.cancel(node);
+                let _ = self.mvar.try_put(original);
+            }
         }
     }
 }
@@ -164,21 +581,262 @@ impl<T> Drop for MVar<T> {
     }
 }
 
+/// One slot in an intrusive, doubly-linked list of waiting futures.
+///
+/// This is embedded directly in `TakeFuture`/`PutFuture` rather than heap
+/// allocated: the future's own (pin-stable) address is the list entry, so
+/// enrolling a waiter never allocates.
+pub(crate) struct WaiterNode {
+    waker: Option<Waker>,
+    next: Option<NonNull<WaiterNode>>,
+    prev: Option<NonNull<WaiterNode>>,
+    state: NodeState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeState {
+    /// not linked into any list
+    Idle,
+    /// linked into a `WaiterList`, waiting to be popped
+    Queued,
+    /// popped from the list and handed a wakeup, but not yet re-polled
+    Woken,
+}
+
+impl WaiterNode {
+    pub(crate) const fn new() -> Self {
+        Self {
+            waker: None,
+            next: None,
+            prev: None,
+            state: NodeState::Idle,
+        }
+    }
+}
+
+// SAFETY: a `WaiterNode`'s pointer fields are only ever read or written while
+// the owning `WaiterList`'s spinlock is held, so it's sound to move one
+// between threads.
+unsafe impl Send for WaiterNode {}
+
+/// A FIFO queue of parked waiters, guarded by a tiny spinlock.
+///
+/// `no_std` rules out `std::sync::Mutex`, and a single `AtomicWaker` can only
+/// ever remember one waiter, so a second concurrent `take()`/`put()` would
+/// silently overwrite (and lose) the first. This keeps every waiter's node
+/// pin-stable inside its own future and threads them together so none of
+/// them are forgotten.
+pub(crate) struct WaiterList {
+    lock: AtomicU8,
+    head: UnsafeCell<Option<NonNull<WaiterNode>>>,
+    tail: UnsafeCell<Option<NonNull<WaiterNode>>>,
+}
+
+impl core::fmt::Debug for WaiterList {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("WaiterList").finish_non_exhaustive()
+    }
+}
+
+// SAFETY: all access to the linked pointers happens while `lock` is held.
+unsafe impl Send for WaiterList {}
+unsafe impl Sync for WaiterList {}
+
+impl WaiterList {
+    pub(crate) const fn new() -> Self {
+        Self {
+            lock: AtomicU8::new(0),
+            head: UnsafeCell::new(None),
+            tail: UnsafeCell::new(None),
+        }
+    }
+
+    fn lock(&self) {
+        while self
+            .lock
+            .compare_exchange_weak(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn unlock(&self) {
+        self.lock.store(0, Ordering::Release);
+    }
+
+    /// Append `node` to the tail. Caller must hold the lock.
+    unsafe fn push_back_locked(&self, mut node: NonNull<WaiterNode>) {
+        let old_tail = self.tail.with_mut(|ptr| unsafe { *ptr });
+
+        unsafe {
+            node.as_mut().prev = old_tail;
+            node.as_mut().next = None;
+        }
+
+        match old_tail {
+            Some(mut tail) => unsafe { tail.as_mut().next = Some(node) },
+            None => self.head.with_mut(|ptr| unsafe { *ptr = Some(node) }),
+        }
+
+        self.tail.with_mut(|ptr| unsafe { *ptr = Some(node) });
+    }
+
+    /// Pop the head node off the list. Caller must hold the lock.
+    unsafe fn pop_front_locked(&self) -> Option<NonNull<WaiterNode>> {
+        let node = self.head.with_mut(|ptr| unsafe { *ptr })?;
+        let next = unsafe { node.as_ref().next };
+
+        self.head.with_mut(|ptr| unsafe { *ptr = next });
+        match next {
+            Some(mut next) => unsafe { next.as_mut().prev = None },
+            None => self.tail.with_mut(|ptr| unsafe { *ptr = None }),
+        }
+
+        Some(node)
+    }
+
+    /// Unlink `node`, which must currently be linked into this list. Caller
+    /// must hold the lock.
+    unsafe fn unlink_locked(&self, node: NonNull<WaiterNode>) {
+        let prev = unsafe { node.as_ref().prev };
+        let next = unsafe { node.as_ref().next };
+
+        match prev {
+            Some(mut prev) => unsafe { prev.as_mut().next = next },
+            None => self.head.with_mut(|ptr| unsafe { *ptr = next }),
+        }
+        match next {
+            Some(mut next) => unsafe { next.as_mut().prev = prev },
+            None => self.tail.with_mut(|ptr| unsafe { *ptr = prev }),
+        }
+    }
+
+    /// Enroll `node` at the tail (or just refresh its waker if it's already
+    /// queued).
+    pub(crate) fn register(&self, node: NonNull<WaiterNode>, waker: &Waker) {
+        self.lock();
+
+        // SAFETY: `node` outlives this call (it's owned by the future that's
+        // calling us), and we hold the lock.
+        let node_ref = unsafe { &mut *node.as_ptr() };
+        node_ref.waker = Some(waker.clone());
+
+        if node_ref.state != NodeState::Queued {
+            node_ref.state = NodeState::Queued;
+            unsafe { self.push_back_locked(node) };
+        }
+
+        self.unlock();
+    }
+
+    /// Drop `node` from the list if it's still enrolled; does nothing if it
+    /// was never enrolled, or already popped by `wake_one`.
+    pub(crate) fn remove(&self, node: NonNull<WaiterNode>) {
+        self.lock();
+
+        let node_ref = unsafe { &mut *node.as_ptr() };
+        if node_ref.state == NodeState::Queued {
+            unsafe { self.unlink_locked(node) };
+        }
+        node_ref.state = NodeState::Idle;
+        node_ref.waker = None;
+
+        self.unlock();
+    }
+
+    /// Pop the head waiter and wake it, if there is one.
+    pub(crate) fn wake_one(&self) {
+        self.lock();
+        let popped = unsafe { self.pop_front_locked() };
+        let waker = popped.and_then(|node| {
+            let node_ref = unsafe { &mut *node.as_ptr() };
+            node_ref.state = NodeState::Woken;
+            // take the waker while still holding the lock: `cancel` also
+            // reads/writes this field, and it's called from whatever thread
+            // drops the waiting future
+            node_ref.waker.take()
+        });
+        self.unlock();
+
+        // don't wake while holding the spinlock: the waker may poll synchronously
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Pop and wake every currently queued waiter, e.g. because every one of
+    /// them can now proceed (unlike `wake_one`, where only the head waiter is
+    /// guaranteed a turn).
+    pub(crate) fn wake_all(&self) {
+        loop {
+            self.lock();
+            let popped = unsafe { self.pop_front_locked() };
+            let waker = popped.and_then(|node| {
+                let node_ref = unsafe { &mut *node.as_ptr() };
+                node_ref.state = NodeState::Woken;
+                node_ref.waker.take()
+            });
+            self.unlock();
+
+            if popped.is_none() {
+                break;
+            }
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Unlink `node` on cancellation. If it had already been popped and
+    /// handed a wakeup that it never got to act on (the future was dropped
+    /// before being re-polled), forward that wakeup to the next waiter so the
+    /// state change it was about to observe isn't lost.
+    pub(crate) fn cancel(&self, node: NonNull<WaiterNode>) {
+        self.lock();
+
+        let node_ref = unsafe { &mut *node.as_ptr() };
+        let was_woken = node_ref.state == NodeState::Woken;
+        if node_ref.state == NodeState::Queued {
+            unsafe { self.unlink_locked(node) };
+        }
+        node_ref.state = NodeState::Idle;
+        node_ref.waker = None;
+
+        let forwarded = if was_woken {
+            unsafe { self.pop_front_locked() }
+        } else {
+            None
+        };
+        let waker = forwarded.and_then(|node| {
+            let node_ref = unsafe { &mut *node.as_ptr() };
+            node_ref.state = NodeState::Woken;
+            node_ref.waker.take()
+        });
+
+        self.unlock();
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
 #[cfg(not(loom))]
 #[derive(Debug)]
-struct UnsafeCell<T>(core::cell::UnsafeCell<T>);
+pub(crate) struct UnsafeCell<T>(core::cell::UnsafeCell<T>);
 
 #[cfg(not(loom))]
 impl<T> UnsafeCell<T> {
-    const fn new(data: T) -> UnsafeCell<T> {
+    pub(crate) const fn new(data: T) -> UnsafeCell<T> {
         UnsafeCell(core::cell::UnsafeCell::new(data))
     }
 
-    //    pub(crate) fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
-    //        f(self.0.get())
-    //    }
+    pub(crate) fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.0.get())
+    }
 
-    fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+    pub(crate) fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
         f(self.0.get())
     }
 }
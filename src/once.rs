@@ -0,0 +1,204 @@
+/// # Asynchronous write-once cell
+///
+/// Modeled on [`spin::Once`](https://docs.rs/spin/latest/spin/struct.Once.html)
+/// and [`once_cell::OnceCell`](https://docs.rs/once_cell), but async: it
+/// starts empty, the first call to `set`/`get_or_init` fills it exactly
+/// once, and after that any number of tasks can read the value concurrently
+/// without ever emptying it again. Built on the same lock-free
+/// state-transition technique as [`crate::MVar`] (and shares its waiter
+/// queue), just with a single-occupancy-forever state machine instead of
+/// take/put.
+use core::future::Future;
+use core::marker::PhantomPinned;
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::task::Poll;
+
+use crate::{UnsafeCell, WaiterList, WaiterNode};
+
+// (empty, initializing, initialized)
+const ONCE_EMPTY: u8 = 1;
+const ONCE_INITIALIZING: u8 = 2;
+const ONCE_INITIALIZED: u8 = 4;
+
+#[derive(Debug)]
+pub struct AsyncOnce<T> {
+    item: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicU8,
+    waiters: WaiterList,
+}
+
+unsafe impl<T: Sync> Sync for AsyncOnce<T> {}
+
+impl<T> Drop for AsyncOnce<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == ONCE_INITIALIZED {
+            self.item.with_mut(|ptr| unsafe { (*ptr).assume_init_drop() });
+        }
+    }
+}
+
+impl<T> Default for AsyncOnce<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> AsyncOnce<T> {
+    pub const fn new() -> Self {
+        Self {
+            item: UnsafeCell::new(MaybeUninit::uninit()),
+            state: AtomicU8::new(ONCE_EMPTY),
+            waiters: WaiterList::new(),
+        }
+    }
+
+    /// Fill the cell if it's empty. Returns `value` back if it was already
+    /// initialized, or another task is initializing it right now.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let can_init = self.state.compare_exchange(
+            ONCE_EMPTY,
+            ONCE_INITIALIZING,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+
+        if can_init.is_ok() {
+            self.item.with_mut(|ptr| unsafe { (*ptr).write(value) });
+
+            self.state.store(ONCE_INITIALIZED, Ordering::SeqCst);
+            self.waiters.wake_all();
+
+            Ok(())
+        } else {
+            Err(value)
+        }
+    }
+
+    /// The value, if the cell has been initialized already. Never blocks.
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::Acquire) == ONCE_INITIALIZED {
+            Some(self.item.with(|ptr| unsafe { (*ptr).assume_init_ref() }))
+        } else {
+            None
+        }
+    }
+
+    /// Get the value, running `init` to produce it if this is the call that
+    /// wins the race to initialize the cell. If another task is already
+    /// initializing it, this awaits that initialization instead of running
+    /// `init` itself: the closure runs at most once.
+    pub const fn get_or_init<F, Fut>(&self, init: F) -> GetOrInitFuture<'_, T, F, Fut>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        GetOrInitFuture {
+            once: self,
+            init: Some(init),
+            phase: InitPhase::Start,
+            node: UnsafeCell::new(WaiterNode::new()),
+            _pin: PhantomPinned,
+        }
+    }
+}
+
+enum InitPhase<Fut> {
+    /// not (yet, or no longer) the task driving initialization
+    Start,
+    /// we won the race to initialize; driving `init`'s future to completion
+    Running(Fut),
+}
+
+#[must_use]
+pub struct GetOrInitFuture<'a, T, F, Fut> {
+    once: &'a AsyncOnce<T>,
+    init: Option<F>,
+    phase: InitPhase<Fut>,
+    node: UnsafeCell<WaiterNode>,
+    _pin: PhantomPinned,
+}
+
+impl<'a, T, F, Fut> Future for GetOrInitFuture<'a, T, F, Fut>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = T>,
+{
+    type Output = &'a T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: we never move out of `self`. `node`'s address is only used
+        // (by value) to link it into the waiter list, and the `Fut` living
+        // inside `phase` is only ever driven through this same `Pin`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let node = NonNull::from(this.node.with_mut(|ptr| unsafe { &mut *ptr }));
+        let mut registered = false;
+
+        loop {
+            if let Some(value) = this.once.get() {
+                this.once.waiters.remove(node);
+                return Poll::Ready(value);
+            }
+
+            match &mut this.phase {
+                InitPhase::Start => {
+                    let can_init = this.once.state.compare_exchange(
+                        ONCE_EMPTY,
+                        ONCE_INITIALIZING,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    );
+
+                    if can_init.is_ok() {
+                        let init = this
+                            .init
+                            .take()
+                            .expect("GetOrInitFuture polled after completion");
+                        this.phase = InitPhase::Running(init());
+                    } else if !registered {
+                        // the winner may finish (and wake_all() an empty
+                        // list) in the gap between the failed CAS above and
+                        // enrolling here, so loop back and re-check `get()`
+                        // once more after registering
+                        this.once.waiters.register(node, cx.waker());
+                        registered = true;
+                    } else {
+                        return Poll::Pending;
+                    }
+                }
+                InitPhase::Running(fut) => {
+                    // SAFETY: `fut` lives inside `self`, which we never move.
+                    let fut = unsafe { Pin::new_unchecked(fut) };
+
+                    match fut.poll(cx) {
+                        Poll::Ready(value) => {
+                            this.once.item.with_mut(|ptr| unsafe { (*ptr).write(value) });
+                            this.once.state.store(ONCE_INITIALIZED, Ordering::SeqCst);
+                            this.once.waiters.wake_all();
+                            this.phase = InitPhase::Start;
+                        }
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T, F, Fut> Drop for GetOrInitFuture<'a, T, F, Fut> {
+    fn drop(&mut self) {
+        let node = NonNull::from(self.node.with_mut(|ptr| unsafe { &mut *ptr }));
+        self.once.waiters.cancel(node);
+
+        if matches!(self.phase, InitPhase::Running(_)) {
+            // we won the race to initialize but got dropped before `init`
+            // finished: hand initialization back to `ONCE_EMPTY` so the next
+            // waiter can pick it up, rather than leaving everyone parked on
+            // an initialization that will never complete
+            self.once.state.store(ONCE_EMPTY, Ordering::SeqCst);
+            self.once.waiters.wake_all();
+        }
+    }
+}
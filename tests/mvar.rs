@@ -0,0 +1,156 @@
+//! Exercises the waiter-queue machinery directly: FIFO wakeups, cancellation
+//! forwarding a wakeup that would otherwise be lost, and `AsyncOnce` running
+//! its initializer exactly once. These don't need an executor - just a
+//! hand-rolled `Waker` and manual polling, since the crate is `no_std` and
+//! has no runtime of its own.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use async_mvar::{AsyncOnce, MVar};
+
+fn flag_waker() -> (Waker, Rc<RefCell<bool>>) {
+    fn clone(data: *const ()) -> RawWaker {
+        unsafe { Rc::increment_strong_count(data as *const RefCell<bool>) };
+        RawWaker::new(data, &VTABLE)
+    }
+    fn wake(data: *const ()) {
+        wake_by_ref(data);
+        drop(unsafe { Rc::from_raw(data as *const RefCell<bool>) });
+    }
+    fn wake_by_ref(data: *const ()) {
+        let flag = unsafe { &*(data as *const RefCell<bool>) };
+        *flag.borrow_mut() = true;
+    }
+    fn drop_fn(data: *const ()) {
+        drop(unsafe { Rc::from_raw(data as *const RefCell<bool>) });
+    }
+
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop_fn);
+
+    let flag = Rc::new(RefCell::new(false));
+    let data = Rc::into_raw(flag.clone()) as *const ();
+    let waker = unsafe { Waker::from_raw(RawWaker::new(data, &VTABLE)) };
+    (waker, flag)
+}
+
+fn poll<F: Future>(fut: Pin<&mut F>, waker: &Waker) -> Poll<F::Output> {
+    fut.poll(&mut Context::from_waker(waker))
+}
+
+#[test]
+fn put_wakes_a_parked_take() {
+    let mvar: MVar<u32> = MVar::new_empty();
+    let (waker, woken) = flag_waker();
+
+    let take = mvar.take();
+    let mut take = Box::pin(take);
+    assert_eq!(poll(take.as_mut(), &waker), Poll::Pending);
+    assert!(!*woken.borrow());
+
+    let mut put = Box::pin(mvar.put(7));
+    assert_eq!(poll(put.as_mut(), &waker), Poll::Ready(()));
+    assert!(*woken.borrow(), "put() must wake the parked take()");
+
+    assert_eq!(poll(take.as_mut(), &waker), Poll::Ready(7));
+}
+
+#[test]
+fn fifo_order_among_parked_takers() {
+    let mvar: MVar<u32> = MVar::new_empty();
+    let (waker_a, woken_a) = flag_waker();
+    let (waker_b, woken_b) = flag_waker();
+
+    let mut take_a = Box::pin(mvar.take());
+    let mut take_b = Box::pin(mvar.take());
+    assert_eq!(poll(take_a.as_mut(), &waker_a), Poll::Pending);
+    assert_eq!(poll(take_b.as_mut(), &waker_b), Poll::Pending);
+
+    let mut put = Box::pin(mvar.put(1));
+    assert_eq!(poll(put.as_mut(), &waker_a), Poll::Ready(()));
+
+    // only the first taker is woken; once it takes the value the cell is
+    // empty again, and the next put wakes the second taker
+    assert!(*woken_a.borrow(), "put() must wake the first parked take()");
+    assert!(!*woken_b.borrow(), "put() must not wake the second take() yet");
+    assert_eq!(poll(take_a.as_mut(), &waker_a), Poll::Ready(1));
+
+    let mut put = Box::pin(mvar.put(2));
+    assert_eq!(poll(put.as_mut(), &waker_b), Poll::Ready(()));
+    assert!(*woken_b.borrow(), "the next put() must wake the second take()");
+    assert_eq!(poll(take_b.as_mut(), &waker_b), Poll::Ready(2));
+}
+
+#[test]
+fn dropping_a_woken_taker_forwards_the_wakeup() {
+    let mvar: MVar<u32> = MVar::new_empty();
+    let (waker_a, _) = flag_waker();
+    let (waker_b, woken_b) = flag_waker();
+
+    let mut take_a = Box::pin(mvar.take());
+    let mut take_b = Box::pin(mvar.take());
+    assert_eq!(poll(take_a.as_mut(), &waker_a), Poll::Pending);
+    assert_eq!(poll(take_b.as_mut(), &waker_b), Poll::Pending);
+
+    let mut put = Box::pin(mvar.put(9));
+    assert_eq!(poll(put.as_mut(), &waker_a), Poll::Ready(()));
+
+    // `take_a` was popped and woken, but we drop it before polling it again;
+    // the wakeup it was carrying must be forwarded to `take_b` instead of
+    // being lost, or `take_b` would park forever even though the cell is full
+    drop(take_a);
+    assert!(*woken_b.borrow(), "cancel() must forward the wakeup it holds");
+    assert_eq!(poll(take_b.as_mut(), &waker_b), Poll::Ready(9));
+}
+
+#[test]
+fn get_or_init_runs_init_once_and_wakes_waiters() {
+    // ready only on its second poll, so there's a window where `a` has won
+    // the race to initialize but hasn't finished yet, and `b` can park
+    struct Step(bool, Option<u32>);
+    impl Future for Step {
+        type Output = u32;
+        fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<u32> {
+            if self.0 {
+                Poll::Ready(self.1.take().expect("polled after completion"))
+            } else {
+                self.0 = true;
+                Poll::Pending
+            }
+        }
+    }
+
+    let once: AsyncOnce<u32> = AsyncOnce::new();
+    let (waker_a, _) = flag_waker();
+    let (waker_b, woken_b) = flag_waker();
+    let runs = Rc::new(RefCell::new(0));
+
+    let runs_a = runs.clone();
+    let mut a = Box::pin(once.get_or_init(move || {
+        *runs_a.borrow_mut() += 1;
+        Step(false, Some(42))
+    }));
+    let runs_b = runs.clone();
+    let mut b = Box::pin(once.get_or_init(move || {
+        *runs_b.borrow_mut() += 1;
+        Step(false, Some(0))
+    }));
+
+    // `a` wins the race to initialize and is mid-flight (its `Step` future
+    // hasn't resolved yet)
+    assert_eq!(poll(a.as_mut(), &waker_a), Poll::Pending);
+    // `b` loses the race and parks
+    assert_eq!(poll(b.as_mut(), &waker_b), Poll::Pending);
+    assert!(!*woken_b.borrow());
+
+    // `a` finishes initializing, which must wake `b` rather than leave it
+    // stuck waiting on an initialization that already completed
+    assert_eq!(poll(a.as_mut(), &waker_a), Poll::Ready(&42));
+    assert!(*woken_b.borrow(), "a completing get_or_init must wake losers");
+    assert_eq!(poll(b.as_mut(), &waker_b), Poll::Ready(&42));
+
+    assert_eq!(*runs.borrow(), 1, "init must run exactly once");
+}